@@ -0,0 +1,180 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+
+use crate::volume::Volume;
+use crate::BlockIndex;
+
+struct CacheEntry {
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+/// Default number of blocks `BlockCache` holds when a caller doesn't pick its own size.
+pub const DEFAULT_CACHE_BLOCKS: usize = 256;
+
+/// A bounded write-back cache of whole `block_size` blocks, wrapping another `Volume`. Reads are
+/// served from the cache when present; writes only touch the cache and are marked dirty until
+/// `flush` (or eviction) pushes them to the wrapped volume. This sits between `Dsfs` and the
+/// underlying `File`/`Volume`, so `BlockTable`, the inode table, and indirect-pointer walks all
+/// share one coherent, bounded view of the device instead of re-reading the same blocks.
+///
+/// `Volume::read_at` takes `&self`, but a cache miss needs to insert into (and possibly evict
+/// from) the cache, so the cache's own state lives behind `RefCell`s, including `volume` itself
+/// (eviction of a dirty entry writes back to it).
+pub struct BlockCache<V: Volume> {
+    volume: RefCell<V>,
+    block_size: u32,
+    capacity: usize,
+    entries: RefCell<HashMap<BlockIndex, CacheEntry>>,
+    /// Recency order, least-recently-used at the front. Re-pushed to the back on every access.
+    order: RefCell<VecDeque<BlockIndex>>,
+}
+
+impl<V: Volume> BlockCache<V> {
+    pub fn new(volume: V, block_size: u32, capacity: usize) -> Self {
+        BlockCache {
+            volume: RefCell::new(volume),
+            block_size,
+            capacity,
+            entries: RefCell::new(HashMap::new()),
+            order: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    fn touch(&self, index: BlockIndex) {
+        let mut order = self.order.borrow_mut();
+        order.retain(|&i| i != index);
+        order.push_back(index);
+    }
+
+    /// Loads `index` into the cache from the wrapped volume if it isn't already cached.
+    fn read_block(&self, index: BlockIndex) -> io::Result<()> {
+        if self.entries.borrow().contains_key(&index) {
+            self.touch(index);
+            return Ok(());
+        }
+        let mut data = vec![0u8; self.block_size as usize];
+        self.volume
+            .borrow()
+            .read_at(&mut data, index as u64 * self.block_size as u64)?;
+        self.insert(index, data, false)?;
+        self.touch(index);
+        Ok(())
+    }
+
+    /// Stores `data` for `index` in the cache and marks it dirty. Nothing reaches the wrapped
+    /// volume until `flush` runs (or the entry is evicted).
+    fn write_block(&self, index: BlockIndex, data: Vec<u8>) -> io::Result<()> {
+        self.insert(index, data, true)?;
+        self.touch(index);
+        Ok(())
+    }
+
+    fn insert(&self, index: BlockIndex, data: Vec<u8>, dirty: bool) -> io::Result<()> {
+        let needs_room = {
+            let entries = self.entries.borrow();
+            !entries.contains_key(&index) && entries.len() >= self.capacity
+        };
+        if needs_room {
+            self.evict()?;
+        }
+        let mut entries = self.entries.borrow_mut();
+        match entries.get_mut(&index) {
+            Some(entry) => {
+                entry.data = data;
+                entry.dirty = entry.dirty || dirty;
+            }
+            None => {
+                entries.insert(index, CacheEntry { data, dirty });
+            }
+        }
+        Ok(())
+    }
+
+    /// Evicts the least-recently-used entry, preferring a clean one so dirty writes aren't
+    /// dropped before `flush` gets a chance to run. If every cached block is dirty, the chosen
+    /// victim must be flushed to the wrapped volume before it can be dropped -- this is a
+    /// write-back cache holding the only copy of bitmap/inode/data writes, so a failed write-back
+    /// is propagated instead of swallowed; the entry stays cached (and dirty) on error rather than
+    /// being silently lost.
+    fn evict(&self) -> io::Result<()> {
+        let mut order = self.order.borrow_mut();
+        let mut entries = self.entries.borrow_mut();
+        let Some(index) = order
+            .iter()
+            .find(|idx| !entries[idx].dirty)
+            .copied()
+            .or_else(|| order.front().copied())
+        else {
+            return Ok(());
+        };
+        if let Some(entry) = entries.get(&index) {
+            if entry.dirty {
+                self.volume
+                    .borrow_mut()
+                    .write_at(&entry.data, index as u64 * self.block_size as u64)?;
+            }
+        }
+        order.retain(|&i| i != index);
+        entries.remove(&index);
+        Ok(())
+    }
+
+    /// Writes every dirty block back to the wrapped volume and clears their dirty bits.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut volume = self.volume.borrow_mut();
+        for (&index, entry) in self.entries.get_mut().iter_mut() {
+            if entry.dirty {
+                volume.write_at(&entry.data, index as u64 * self.block_size as u64)?;
+                entry.dirty = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<V: Volume> Volume for BlockCache<V> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let block_size = self.block_size as u64;
+        let mut remaining = buf.len();
+        let mut buf_pos = 0;
+        let mut cur_offset = offset;
+        while remaining > 0 {
+            let index = (cur_offset / block_size) as BlockIndex;
+            let offset_in_block = (cur_offset % block_size) as usize;
+            let chunk_len = remaining.min(block_size as usize - offset_in_block);
+            self.read_block(index)?;
+            let entries = self.entries.borrow();
+            let block = &entries[&index].data;
+            buf[buf_pos..buf_pos + chunk_len]
+                .copy_from_slice(&block[offset_in_block..offset_in_block + chunk_len]);
+            buf_pos += chunk_len;
+            cur_offset += chunk_len as u64;
+            remaining -= chunk_len;
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let block_size = self.block_size as u64;
+        let mut remaining = buf.len();
+        let mut buf_pos = 0;
+        let mut cur_offset = offset;
+        while remaining > 0 {
+            let index = (cur_offset / block_size) as BlockIndex;
+            let offset_in_block = (cur_offset % block_size) as usize;
+            let chunk_len = remaining.min(block_size as usize - offset_in_block);
+            // Load the existing block first so a partial-block write doesn't clobber the rest.
+            self.read_block(index)?;
+            let mut data = self.entries.borrow()[&index].data.clone();
+            data[offset_in_block..offset_in_block + chunk_len]
+                .copy_from_slice(&buf[buf_pos..buf_pos + chunk_len]);
+            self.write_block(index, data)?;
+            buf_pos += chunk_len;
+            cur_offset += chunk_len as u64;
+            remaining -= chunk_len;
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,23 @@
-use std::{error, fs::File, os::unix::fs::FileExt, path::PathBuf};
+use std::{fs::File, path::PathBuf};
 
 use thiserror::Error;
 
+use crate::block_cache::{BlockCache, DEFAULT_CACHE_BLOCKS};
 use crate::fs_structs::{
-    block_table::{BlockTable, BlockTableError},
-    super_block::SuperBlock,
+    block_table::{BlockTable, BlockTableError, BlockType},
+    dir_entry::{DirEntry, DirEntryType},
+    inode::{write_inode, Inode, InodeError, S_IFDIR},
+    super_block::{SuperBlock, SuperBlockError},
 };
+use crate::volume::Volume;
+use crate::BlockIndex;
 
-pub struct Dsfs {
-    pub block_file: File,
+/// Inode number of the filesystem root. This matches FUSE's root inode number (1), so the FUSE
+/// callbacks can use the inode numbers they receive as dsfs inode numbers directly.
+pub const ROOT_INO: u32 = 1;
+
+pub struct Dsfs<V: Volume = File> {
+    pub block_file: V,
     mount_point: PathBuf,
     pub block_size: u32,
     pub num_blocks: u32,
@@ -18,19 +27,26 @@ pub struct Dsfs {
 }
 
 #[derive(Error, Debug)]
-enum DsfsError {
+pub enum DsfsError {
     #[error("File IO Error")]
     IoError(#[from] std::io::Error),
     #[error("Block Table Error: {0}")]
     BlockTableError(#[from] BlockTableError),
+    #[error("Super Block Error: {0}")]
+    SuperBlockError(#[from] SuperBlockError),
+    #[error("Inode Error: {0}")]
+    InodeError(#[from] InodeError),
+    #[error("Ran out of free blocks while formatting the filesystem")]
+    OutOfSpace,
+    #[error("Freshly formatted image failed its own sanity check")]
+    InconsistentImage,
 }
 
-impl Dsfs {
-    // Loads an existing filesystem from a block file
-    pub fn load(file_name: PathBuf, mount_point: PathBuf) -> Result<Self, DsfsError> {
+impl<V: Volume> Dsfs<V> {
+    /// Mounts a filesystem that already lives on `block_file` (as opposed to `create_fs`, which
+    /// formats a fresh one).
+    pub fn load_from_volume(block_file: V, mount_point: PathBuf) -> Result<Self, DsfsError> {
         // Read superblock information
-        let block_file = File::open(file_name)?;
-
         let SuperBlock {
             block_size,
             num_blocks,
@@ -56,38 +72,151 @@ impl Dsfs {
         Ok(dsfs)
     }
 
-    fn create(
-        file_name: PathBuf,
+    /// Finds the first free block across all groups, marks it as `kind`, and returns its
+    /// absolute index. Writes back only the group table that actually changed.
+    pub fn alloc(&mut self, kind: BlockType) -> Result<Option<BlockIndex>, BlockTableError> {
+        // Temporarily take block_table out of self so we can hand `&mut Dsfs` (minus the tables
+        // we're scanning) to BlockTable::allocate_block/write_table without aliasing
+        // self.block_table.
+        let mut tables = std::mem::take(&mut self.block_table);
+        let mut allocated = None;
+        for table in tables.iter_mut() {
+            if let Some(block) = table.allocate_block(self, kind)? {
+                table.write_table(self)?;
+                allocated = Some(block);
+                break;
+            }
+        }
+        self.block_table = tables;
+        Ok(allocated)
+    }
+
+    /// Resets the given block's entry back to `BlockType::Free`.
+    pub fn free(&mut self, block: BlockIndex) -> Result<(), BlockTableError> {
+        let group_index = block / self.blocks_in_group;
+        let local_index = block % self.blocks_in_group;
+        let mut tables = std::mem::take(&mut self.block_table);
+        let result = (|| -> Result<(), BlockTableError> {
+            let table = tables
+                .get_mut(group_index as usize)
+                .ok_or(BlockTableError::OutOfBounds(block, self.num_blocks))?;
+            table.set_type(local_index, self, BlockType::Free)?;
+            table.write_table(self)
+        })();
+        self.block_table = tables;
+        result
+    }
+
+    /// Formats a fresh filesystem directly onto `volume`: writes the superblock, lays down each
+    /// group's block and inode tables, and creates a root directory inode with `.`/`..` entries.
+    /// `create_fs` wraps this for the `File`-backed path (which also has to size the backing
+    /// file first); this generic entry point lets tests format a `MemVolume` directly.
+    pub(crate) fn format_volume(
+        mut volume: V,
         mount_point: PathBuf,
+        num_blocks: u32,
         block_size: u32,
     ) -> Result<Self, DsfsError> {
-        // Read superblock information
-        let block_file = File::open(file_name)?;
-
-        let mut blocks_in_group_buf = [0 as u8; 4];
-        let _ = block_file.read_exact_at(&mut blocks_in_group_buf, 8)?;
-        let blocks_in_group = u32::from_be_bytes(blocks_in_group_buf);
-
-        let SuperBlock {
+        // blocks_in_group is always block_size; see the comment on Dsfs::blocks_in_group.
+        let blocks_in_group = block_size;
+        SuperBlock {
             block_size,
             num_blocks,
-        } = SuperBlock::new(&block_file)?;
+        }
+        .write(&mut volume)?;
 
-        // Number of groups is ceil(num_blocks/blocks_in_group)
         let num_groups = num_blocks.div_ceil(blocks_in_group);
         let mut dsfs = Dsfs {
-            block_file,
+            block_file: volume,
             mount_point,
             block_size,
             num_blocks,
             blocks_in_group,
             block_table: vec![],
         };
-        // For all groups, load a free table
         for group_index in 0..num_groups {
-            dsfs.block_table
-                .push(BlockTable::from_fs(&dsfs, group_index)?)
+            let table = BlockTable::create_and_init(&mut dsfs, group_index)?;
+            dsfs.block_table.push(table);
         }
+
+        Self::init_root_dir(&mut dsfs)?;
+        Ok(dsfs)
+    }
+
+    /// Allocates a data block for the root directory and writes its inode with `.`/`..`
+    /// entries, both pointing back at `ROOT_INO`.
+    fn init_root_dir(dsfs: &mut Dsfs<V>) -> Result<(), DsfsError> {
+        let data_block = dsfs.alloc(BlockType::Data)?.ok_or(DsfsError::OutOfSpace)?;
+
+        let mut entries = Vec::new();
+        for name in [".", ".."] {
+            entries.extend_from_slice(
+                &DirEntry {
+                    inode: ROOT_INO,
+                    file_type: DirEntryType::Directory,
+                    name: name.to_string(),
+                }
+                .to_bytes(),
+            );
+        }
+        dsfs.block_file
+            .write_at(&entries, data_block as u64 * dsfs.block_size as u64)?;
+
+        let mut root_inode = Inode::new(S_IFDIR | 0o755, 0, 0);
+        root_inode.links_count = 2; // "." plus the parent's entry pointing at us
+        root_inode.size = entries.len() as u64;
+        root_inode.direct[0] = data_block;
+        write_inode(dsfs, ROOT_INO, root_inode)?;
+
+        Ok(())
+    }
+}
+
+impl Dsfs<BlockCache<File>> {
+    /// Loads an existing filesystem from a block file, caching its blocks with
+    /// `DEFAULT_CACHE_BLOCKS` of headroom.
+    pub fn load(file_name: PathBuf, mount_point: PathBuf) -> Result<Self, DsfsError> {
+        let block_file = File::open(file_name)?;
+        // The cache needs block_size to chunk reads, but we only learn it from the superblock
+        // that lives on the volume we're about to wrap -- read it once through the plain File
+        // first.
+        let SuperBlock { block_size, .. } = SuperBlock::new(&block_file)?;
+        let block_file = BlockCache::new(block_file, block_size, DEFAULT_CACHE_BLOCKS);
+        Self::load_from_volume(block_file, mount_point)
+    }
+
+    /// Formats a fresh dsfs image at `file_name`: truncates the backing file to
+    /// `num_blocks * block_size`, writes the superblock, lays down each group's block and
+    /// inode tables, and creates a root directory inode with `.`/`..` entries.
+    pub fn create_fs(
+        file_name: PathBuf,
+        mount_point: PathBuf,
+        num_blocks: u32,
+        block_size: u32,
+    ) -> Result<Self, DsfsError> {
+        let mut raw_file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&file_name)?;
+        raw_file.set_len(num_blocks as u64 * block_size as u64)?;
+
+        let block_file = BlockCache::new(raw_file, block_size, DEFAULT_CACHE_BLOCKS);
+        let mut dsfs = Self::format_volume(block_file, mount_point, num_blocks, block_size)?;
+        dsfs.block_file.flush()?;
+
+        // Reread what we just wrote and sanity-check it before handing back a mounted fs, the
+        // same discipline FAT drivers use to validate a BPB right after formatting.
+        let num_groups = num_blocks.div_ceil(block_size);
+        let reread = SuperBlock::new(&dsfs.block_file)?;
+        if reread.block_size != block_size
+            || reread.num_blocks != num_blocks
+            || reread.num_blocks.div_ceil(reread.block_size) != num_groups
+        {
+            return Err(DsfsError::InconsistentImage);
+        }
+
         Ok(dsfs)
     }
 }
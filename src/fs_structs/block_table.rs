@@ -1,11 +1,18 @@
-use std::os::unix::fs::FileExt;
 use thiserror::Error;
 
-use crate::{dsfs::Dsfs, GroupIndex};
+use crate::fs_structs::inode::INODE_TABLE_BLOCKS;
+use crate::volume::Volume;
+use crate::{dsfs::Dsfs, BlockIndex, GroupIndex};
 
 pub struct BlockTable {
     table: Vec<BlockType>,
     group_index: GroupIndex,
+    /// Number of `BlockType::Free` entries currently in `table`, kept in sync by `set_type` so
+    /// `allocate_block` can bail out early instead of scanning a full group.
+    free_count: u32,
+    /// Index to resume scanning from on the next `allocate_block` call, so repeated allocations
+    /// don't rescan entries we already know are taken.
+    next_free_hint: u32,
 }
 
 #[derive(Error, Debug)]
@@ -18,13 +25,15 @@ pub enum BlockTableError {
     TypeCastError(&'static str, &'static str),
     #[error("Invalid block type byte {0}")]
     InvalidBlockType(u8),
+    #[error("free_count said group {1} had a free block but none was found (free_count: {0})")]
+    CorruptFreeCount(u32, GroupIndex),
 }
 
 // NOTE: EXTREMELY IMPORTANT!!!! Do not change this type without ensuring that TryFrom<u8> for
 // BlockType is updated!! Not updating this trait impl can and will lead to UB
 #[repr(u8)]
-#[derive(Copy, Clone)]
-enum BlockType {
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum BlockType {
     Free = 0x0,
     SuperBlock = 0x1,
     BlockTable = 0x2,
@@ -85,22 +94,54 @@ impl BlockTable {
         ])
     }
 
+    /// Counts the number of `BlockType::Free` entries in a table
+    #[inline]
+    fn count_free(table: &[BlockType]) -> u32 {
+        table.iter().filter(|&&t| t == BlockType::Free).count() as u32
+    }
+
+    /// Number of entries in this group's table that actually back a real block. Every group is
+    /// allocated a full `blocks_in_group`-sized table, but the last group only covers whatever is
+    /// left of `num_blocks` -- the remainder is table padding, not allocatable space.
+    fn valid_len<V: Volume>(dsfs: &Dsfs<V>, group_index: GroupIndex) -> u32 {
+        let group_start = group_index * dsfs.blocks_in_group;
+        dsfs.num_blocks
+            .saturating_sub(group_start)
+            .min(dsfs.blocks_in_group)
+    }
+
     /// Creates a new block table, writes it to the disk, and returns it
-    fn create_and_init(dsfs: &Dsfs, group_index: GroupIndex) -> Result<Self, BlockTableError> {
+    pub(crate) fn create_and_init<V: Volume>(
+        dsfs: &mut Dsfs<V>,
+        group_index: GroupIndex,
+    ) -> Result<Self, BlockTableError> {
         let mut table = Self::new_table(dsfs.blocks_in_group)?;
         // If the first group, the first block is the superblock and the second is the block table.
         // Else, the first block is the block table.
-        match group_index {
+        let block_table_index = match group_index {
             0 => {
                 table[0] = BlockType::SuperBlock;
-                table[1] = BlockType::BlockTable;
-            }
-            _ => {
-                table[0] = BlockType::BlockTable;
+                1
             }
+            _ => 0,
+        };
+        table[block_table_index] = BlockType::BlockTable;
+        // The inode table for this group lives right after its block table.
+        let inode_start = block_table_index + 1;
+        let inode_end = (inode_start + INODE_TABLE_BLOCKS as usize).min(table.len());
+        for entry in &mut table[inode_start..inode_end] {
+            *entry = BlockType::Inode;
+        }
+        // Construct the block table. Only count free entries within this group's real block
+        // range -- the rest of a partial last group's table is padding, not free space.
+        let valid_len = Self::valid_len(dsfs, group_index);
+        let free_count = Self::count_free(&table[..valid_len as usize]);
+        let block_table = BlockTable {
+            table,
+            group_index,
+            free_count,
+            next_free_hint: 0,
         };
-        // Construct the block table
-        let block_table = BlockTable { table, group_index };
         // Write it to the disk
         match block_table.write_table(dsfs) {
             Ok(_) => Ok(block_table),
@@ -109,17 +150,29 @@ impl BlockTable {
     }
 
     /// Creates a BlockTable from an existing block table on the fs
-    pub fn from_fs(dsfs: &Dsfs, group_index: GroupIndex) -> Result<BlockTable, BlockTableError> {
+    pub fn from_fs<V: Volume>(
+        dsfs: &Dsfs<V>,
+        group_index: GroupIndex,
+    ) -> Result<BlockTable, BlockTableError> {
         let table = Self::new_table(dsfs.blocks_in_group)?;
-        let mut block_table = BlockTable { table, group_index };
+        let mut block_table = BlockTable {
+            table,
+            group_index,
+            free_count: 0,
+            next_free_hint: 0,
+        };
         match block_table.read_table(dsfs) {
-            Ok(_) => Ok(block_table),
+            Ok(_) => {
+                let valid_len = Self::valid_len(dsfs, group_index);
+                block_table.free_count = Self::count_free(&block_table.table[..valid_len as usize]);
+                Ok(block_table)
+            }
             Err(err) => Err(err),
         }
     }
 
     /// Writes table state from memory to disk
-    fn write_table(&self, dsfs: &Dsfs) -> Result<(), BlockTableError> {
+    pub(crate) fn write_table<V: Volume>(&self, dsfs: &mut Dsfs<V>) -> Result<(), BlockTableError> {
         let block_index = match self.group_index {
             0 => 1,
             // block_size is being used here to get the blocks in a group
@@ -127,7 +180,7 @@ impl BlockTable {
             // groups block table.
             _ => dsfs.block_size * self.group_index,
         };
-        match dsfs.block_file.write_all_at(
+        match dsfs.block_file.write_at(
             self.table_as_bytes().as_slice(),
             (block_index * dsfs.block_size).into(),
         ) {
@@ -137,7 +190,7 @@ impl BlockTable {
     }
 
     /// Reads table state from disk to memory
-    fn read_table(&mut self, dsfs: &Dsfs) -> Result<(), BlockTableError> {
+    fn read_table<V: Volume>(&mut self, dsfs: &Dsfs<V>) -> Result<(), BlockTableError> {
         let block_index = match self.group_index {
             0 => 1,
             _ => dsfs.blocks_in_group * self.group_index,
@@ -153,7 +206,7 @@ impl BlockTable {
         ];
         match dsfs
             .block_file
-            .read_exact_at(&mut table, (block_index * dsfs.block_size).into())
+            .read_at(&mut table, (block_index * dsfs.block_size).into())
         {
             Ok(_) => {
                 self.table = Self::table_from_bytes(table)?;
@@ -167,12 +220,12 @@ impl BlockTable {
     /// NOTE: This function only updates the table in memory. You must call write_table() at some
     /// point to actually write the changes. This is seperated so multiple type changes can be
     /// written to the disk atomically, and to reduce the number of IO operations.
-    fn set_type(
+    pub(crate) fn set_type<V: Volume>(
         &mut self,
         block_in_group_index: u32, // This is the index of the byte inside the current block table. This is NOT
         // the same as the block index. It will be block_index % BLOCKS_IN_GROUP b/c it is the
         // index of the block within a group
-        fs: &Dsfs,
+        fs: &Dsfs<V>,
         value: BlockType,
     ) -> Result<(), BlockTableError> {
         // TODO: Check this condition (maybe off by 1)
@@ -182,7 +235,14 @@ impl BlockTable {
                 fs.blocks_in_group,
             ));
         }
-        self.table[block_in_group_index as usize] = value;
+        let slot = &mut self.table[block_in_group_index as usize];
+        // Keep free_count in sync so allocate_block can trust it without rescanning.
+        match (*slot == BlockType::Free, value == BlockType::Free) {
+            (true, false) => self.free_count -= 1,
+            (false, true) => self.free_count += 1,
+            _ => {}
+        }
+        *slot = value;
         Ok(())
     }
 
@@ -190,10 +250,10 @@ impl BlockTable {
     /// NOTE: This only gets the type from the table in memory. You must call read_table() before
     /// this function to get any potentially changed data. This is seperated so you can make
     /// multiple consecutive calls to this function with only a single IO operation.
-    fn get_type(
+    fn get_type<V: Volume>(
         &mut self,
         block_in_group_index: u32, // Ditto
-        fs: &Dsfs,
+        fs: &Dsfs<V>,
     ) -> Result<BlockType, BlockTableError> {
         // TODO: Check this condition (maybe off by 1)
         if block_in_group_index >= fs.blocks_in_group {
@@ -204,4 +264,81 @@ impl BlockTable {
         }
         Ok(self.table[block_in_group_index as usize])
     }
+
+    /// Scans this group's table for the first free block, marks it with `kind`, and returns its
+    /// absolute block index (i.e. across the whole device, not just this group). Returns `Ok(None)`
+    /// if the group has no free blocks.
+    /// NOTE: Only updates the table in memory, same as `set_type` - call `write_table()` to commit.
+    pub fn allocate_block<V: Volume>(
+        &mut self,
+        fs: &Dsfs<V>,
+        kind: BlockType,
+    ) -> Result<Option<BlockIndex>, BlockTableError> {
+        if self.free_count == 0 {
+            return Ok(None);
+        }
+        // Bound the scan to this group's real block count -- a partial last group's table has
+        // padding past num_blocks that must never be handed out as an allocatable block.
+        let len = Self::valid_len(fs, self.group_index);
+        for offset in 0..len {
+            let index = (self.next_free_hint + offset) % len;
+            if self.get_type(index, fs)? == BlockType::Free {
+                self.set_type(index, fs, kind)?;
+                self.next_free_hint = index + 1;
+                return Ok(Some(self.group_index * fs.blocks_in_group + index));
+            }
+        }
+        // free_count said there should have been one; treat as corrupted state rather than panic.
+        Err(BlockTableError::CorruptFreeCount(self.free_count, self.group_index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsfs::Dsfs;
+    use crate::volume::MemVolume;
+
+    fn test_fs(num_blocks: u32, block_size: u32) -> Dsfs<MemVolume> {
+        let volume = MemVolume::new(num_blocks as usize * block_size as usize);
+        Dsfs::format_volume(volume, "/mnt".into(), num_blocks, block_size).unwrap()
+    }
+
+    #[test]
+    fn allocate_block_wraps_the_scan_around_via_next_free_hint() {
+        // block_size=40 leaves 6 free blocks (34..39) after the fixed group overhead (1
+        // superblock + 1 block table + 32 inode table blocks); format_volume's root directory
+        // setup eats one of those, leaving 5 for this test to exhaust.
+        let mut fs = test_fs(40, 40);
+
+        let mut allocated = Vec::new();
+        for _ in 0..5 {
+            allocated.push(fs.alloc(BlockType::Data).unwrap().unwrap());
+        }
+        assert!(fs.alloc(BlockType::Data).unwrap().is_none());
+
+        // Freeing the earliest-allocated block and allocating again forces next_free_hint (now
+        // past the end of the table) to wrap back around to find it, rather than only scanning
+        // forward from where the last allocation left off.
+        let freed = allocated[0];
+        fs.free(freed).unwrap();
+        assert_eq!(fs.alloc(BlockType::Data).unwrap(), Some(freed));
+    }
+
+    #[test]
+    fn allocate_block_never_hands_out_a_partial_last_groups_padding() {
+        // block_size=40: group 0 spans blocks 0..39 (full, 6 free minus 1 the root directory
+        // takes during formatting), group 1 spans blocks 40..49 (only 10 real blocks). Group 1's
+        // in-memory table is still 40 entries long, but its block table + inode table slots alone
+        // eat all 10 real entries, so valid_len must keep the padding entries (indices 10..39)
+        // from ever being scanned as free.
+        let mut fs = test_fs(50, 40);
+
+        for _ in 0..5 {
+            assert!(fs.alloc(BlockType::Data).unwrap().is_some());
+        }
+        // Group 0 is now full and group 1 has no real free blocks left; if valid_len were
+        // ignored, this would incorrectly hand out a block index past num_blocks.
+        assert!(fs.alloc(BlockType::Data).unwrap().is_none());
+    }
 }
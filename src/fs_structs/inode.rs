@@ -0,0 +1,363 @@
+use thiserror::Error;
+
+use crate::volume::Volume;
+use crate::{dsfs::Dsfs, BlockIndex};
+
+/// Number of direct block pointers stored in an inode, same as ext2.
+pub const NUM_DIRECT: usize = 12;
+
+/// On-disk size of a single inode, in bytes. The fixed fields plus the pointer array need
+/// 100 bytes; round up to a multiple of 8 to leave a little room to grow.
+pub const INODE_SIZE: u32 = 104;
+
+/// Number of blocks reserved for the inode table in each block group.
+/// Fixed for now; revisit if the inode count needs to scale with group size.
+pub(crate) const INODE_TABLE_BLOCKS: u32 = 32;
+
+pub type InodeNumber = u32;
+
+/// POSIX file-type bits within `Inode::mode`, same encoding as ext2/`st_mode`.
+pub const S_IFMT: u16 = 0o170000;
+pub const S_IFDIR: u16 = 0o040000;
+pub const S_IFREG: u16 = 0o100000;
+
+#[derive(Error, Debug)]
+pub enum InodeError {
+    #[error("File IO Error")]
+    IoError(#[from] std::io::Error),
+    #[error("Inode number {0} is out of bounds. Max inode number: {1}")]
+    OutOfBounds(InodeNumber, InodeNumber),
+    #[error("Byte offset {0} is beyond what the block pointer tree can address")]
+    OffsetTooLarge(u64),
+}
+
+/// Per-file metadata plus the direct/indirect block pointer tree, modeled on ext2's inode.
+#[derive(Clone, Copy)]
+pub struct Inode {
+    pub mode: u16,
+    pub uid: u16,
+    pub gid: u16,
+    pub links_count: u16,
+    pub size: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    /// Data blocks named directly by index.
+    pub direct: [BlockIndex; NUM_DIRECT],
+    /// Points to a block full of direct `BlockIndex` entries.
+    pub single_indirect: BlockIndex,
+    /// Points to a block full of single-indirect pointers.
+    pub double_indirect: BlockIndex,
+    /// Points to a block full of double-indirect pointers.
+    pub triple_indirect: BlockIndex,
+}
+
+impl Inode {
+    pub fn new(mode: u16, uid: u16, gid: u16) -> Self {
+        Inode {
+            mode,
+            uid,
+            gid,
+            links_count: 1,
+            size: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            direct: [0; NUM_DIRECT],
+            single_indirect: 0,
+            double_indirect: 0,
+            triple_indirect: 0,
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(INODE_SIZE as usize);
+        bytes.extend_from_slice(&self.mode.to_be_bytes());
+        bytes.extend_from_slice(&self.uid.to_be_bytes());
+        bytes.extend_from_slice(&self.gid.to_be_bytes());
+        bytes.extend_from_slice(&self.links_count.to_be_bytes());
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&self.atime.to_be_bytes());
+        bytes.extend_from_slice(&self.mtime.to_be_bytes());
+        bytes.extend_from_slice(&self.ctime.to_be_bytes());
+        for ptr in self.direct {
+            bytes.extend_from_slice(&ptr.to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.single_indirect.to_be_bytes());
+        bytes.extend_from_slice(&self.double_indirect.to_be_bytes());
+        bytes.extend_from_slice(&self.triple_indirect.to_be_bytes());
+        // Pad out to INODE_SIZE so the on-disk layout has room to grow.
+        bytes.resize(INODE_SIZE as usize, 0);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let mut direct = [0 as BlockIndex; NUM_DIRECT];
+        let mut offset = 40; // mode + uid + gid + links_count + size + atime + mtime + ctime
+        for ptr in direct.iter_mut() {
+            *ptr = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap());
+            offset += 4;
+        }
+        Inode {
+            mode: u16::from_be_bytes(bytes[0..2].try_into().unwrap()),
+            uid: u16::from_be_bytes(bytes[2..4].try_into().unwrap()),
+            gid: u16::from_be_bytes(bytes[4..6].try_into().unwrap()),
+            links_count: u16::from_be_bytes(bytes[6..8].try_into().unwrap()),
+            size: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
+            atime: u64::from_be_bytes(bytes[16..24].try_into().unwrap()),
+            mtime: u64::from_be_bytes(bytes[24..32].try_into().unwrap()),
+            ctime: u64::from_be_bytes(bytes[32..40].try_into().unwrap()),
+            direct,
+            single_indirect: u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()),
+            double_indirect: u32::from_be_bytes(
+                bytes[offset + 4..offset + 8].try_into().unwrap(),
+            ),
+            triple_indirect: u32::from_be_bytes(
+                bytes[offset + 8..offset + 12].try_into().unwrap(),
+            ),
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    /// Walks the direct/indirect pointer tree to find the data block holding `byte_offset`.
+    /// A zero pointer anywhere along the walk means a hole, so this returns `Ok(None)`
+    /// instead of allocating anything.
+    pub fn block_for_offset<V: Volume>(
+        &self,
+        fs: &Dsfs<V>,
+        byte_offset: u64,
+    ) -> Result<Option<BlockIndex>, InodeError> {
+        let block_no = byte_offset / fs.block_size as u64;
+        let entries_per_block = (fs.block_size / 4) as u64;
+
+        if block_no < NUM_DIRECT as u64 {
+            return Ok(non_zero(self.direct[block_no as usize]));
+        }
+        let mut remaining = block_no - NUM_DIRECT as u64;
+
+        if remaining < entries_per_block {
+            return self.indirect_lookup(fs, self.single_indirect, remaining);
+        }
+        remaining -= entries_per_block;
+
+        if remaining < entries_per_block * entries_per_block {
+            let outer_index = remaining / entries_per_block;
+            let inner_index = remaining % entries_per_block;
+            let Some(single_indirect) = self.indirect_pointer(fs, self.double_indirect, outer_index)? else {
+                return Ok(None);
+            };
+            return self.indirect_lookup(fs, single_indirect, inner_index);
+        }
+        remaining -= entries_per_block * entries_per_block;
+
+        let triple_span = entries_per_block * entries_per_block * entries_per_block;
+        if remaining >= triple_span {
+            return Err(InodeError::OffsetTooLarge(byte_offset));
+        }
+        let outer_index = remaining / (entries_per_block * entries_per_block);
+        let mid_index = (remaining / entries_per_block) % entries_per_block;
+        let inner_index = remaining % entries_per_block;
+        let Some(double_indirect) = self.indirect_pointer(fs, self.triple_indirect, outer_index)?
+        else {
+            return Ok(None);
+        };
+        let Some(single_indirect) = self.indirect_pointer(fs, double_indirect, mid_index)? else {
+            return Ok(None);
+        };
+        self.indirect_lookup(fs, single_indirect, inner_index)
+    }
+
+    /// Reads the `index`th `BlockIndex` entry out of the indirection block at `block`,
+    /// returning `None` if `block` itself is a hole (zero pointer).
+    fn indirect_pointer<V: Volume>(
+        &self,
+        fs: &Dsfs<V>,
+        block: BlockIndex,
+        index: u64,
+    ) -> Result<Option<BlockIndex>, InodeError> {
+        if block == 0 {
+            return Ok(None);
+        }
+        let mut entry_buf = [0u8; 4];
+        fs.block_file.read_at(
+            &mut entry_buf,
+            block as u64 * fs.block_size as u64 + index * 4,
+        )?;
+        Ok(non_zero(u32::from_be_bytes(entry_buf)))
+    }
+
+    /// Same as `indirect_pointer`, but the result is itself a data block pointer rather
+    /// than another indirection block.
+    fn indirect_lookup<V: Volume>(
+        &self,
+        fs: &Dsfs<V>,
+        block: BlockIndex,
+        index: u64,
+    ) -> Result<Option<BlockIndex>, InodeError> {
+        self.indirect_pointer(fs, block, index)
+    }
+}
+
+/// Absolute starting block of the inode table for the given group: right after that
+/// group's block table, mirroring `BlockTable`'s own start-block arithmetic.
+fn inode_table_start_block<V: Volume>(fs: &Dsfs<V>, group_index: u32) -> BlockIndex {
+    let block_table_block = match group_index {
+        0 => 1,
+        _ => fs.blocks_in_group * group_index,
+    };
+    block_table_block + 1
+}
+
+fn inodes_per_group<V: Volume>(fs: &Dsfs<V>) -> u32 {
+    (INODE_TABLE_BLOCKS * fs.block_size) / INODE_SIZE
+}
+
+fn locate<V: Volume>(fs: &Dsfs<V>, ino: InodeNumber) -> Result<u64, InodeError> {
+    let per_group = inodes_per_group(fs);
+    let total = per_group * fs.num_blocks.div_ceil(fs.blocks_in_group);
+    if ino == 0 || ino > total {
+        return Err(InodeError::OutOfBounds(ino, total));
+    }
+    let index = ino - 1;
+    let group_index = index / per_group;
+    let index_in_group = index % per_group;
+    let start_block = inode_table_start_block(fs, group_index);
+    Ok(start_block as u64 * fs.block_size as u64 + index_in_group as u64 * INODE_SIZE as u64)
+}
+
+pub fn read_inode<V: Volume>(fs: &Dsfs<V>, ino: InodeNumber) -> Result<Inode, InodeError> {
+    let offset = locate(fs, ino)?;
+    let mut buf = vec![0u8; INODE_SIZE as usize];
+    fs.block_file.read_at(&mut buf, offset)?;
+    Ok(Inode::from_bytes(&buf))
+}
+
+pub fn write_inode<V: Volume>(
+    fs: &mut Dsfs<V>,
+    ino: InodeNumber,
+    inode: Inode,
+) -> Result<(), InodeError> {
+    let offset = locate(fs, ino)?;
+    fs.block_file.write_at(&inode.to_bytes(), offset)?;
+    Ok(())
+}
+
+fn non_zero(ptr: BlockIndex) -> Option<BlockIndex> {
+    if ptr == 0 {
+        None
+    } else {
+        Some(ptr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_structs::block_table::BlockType;
+    use crate::volume::MemVolume;
+
+    const BLOCK_SIZE: u32 = 64;
+    const NUM_BLOCKS: u32 = 64;
+
+    fn test_fs() -> Dsfs<MemVolume> {
+        let volume = MemVolume::new(NUM_BLOCKS as usize * BLOCK_SIZE as usize);
+        Dsfs::format_volume(volume, "/mnt".into(), NUM_BLOCKS, BLOCK_SIZE).unwrap()
+    }
+
+    /// Writes a single `BlockIndex` pointer entry into an indirection block, same layout
+    /// `indirect_pointer` reads back.
+    fn write_ptr(fs: &mut Dsfs<MemVolume>, block: BlockIndex, index: u64, value: BlockIndex) {
+        fs.block_file
+            .write_at(
+                &value.to_be_bytes(),
+                block as u64 * fs.block_size as u64 + index * 4,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn block_for_offset_resolves_direct_pointer() {
+        let mut fs = test_fs();
+        let data_block = fs.alloc(BlockType::Data).unwrap().unwrap();
+        let mut inode = Inode::new(S_IFREG, 0, 0);
+        inode.direct[3] = data_block;
+
+        let offset = 3 * fs.block_size as u64 + 5;
+        assert_eq!(
+            inode.block_for_offset(&fs, offset).unwrap(),
+            Some(data_block)
+        );
+    }
+
+    #[test]
+    fn block_for_offset_resolves_single_indirect() {
+        let mut fs = test_fs();
+        let indirect_block = fs.alloc(BlockType::IndirectionTable).unwrap().unwrap();
+        let data_block = fs.alloc(BlockType::Data).unwrap().unwrap();
+        write_ptr(&mut fs, indirect_block, 0, data_block);
+
+        let mut inode = Inode::new(S_IFREG, 0, 0);
+        inode.single_indirect = indirect_block;
+
+        let offset = NUM_DIRECT as u64 * fs.block_size as u64;
+        assert_eq!(
+            inode.block_for_offset(&fs, offset).unwrap(),
+            Some(data_block)
+        );
+    }
+
+    #[test]
+    fn block_for_offset_resolves_double_indirect() {
+        let mut fs = test_fs();
+        let double_block = fs.alloc(BlockType::IndirectionTable).unwrap().unwrap();
+        let single_block = fs.alloc(BlockType::IndirectionTable).unwrap().unwrap();
+        let data_block = fs.alloc(BlockType::Data).unwrap().unwrap();
+        write_ptr(&mut fs, double_block, 0, single_block);
+        write_ptr(&mut fs, single_block, 0, data_block);
+
+        let mut inode = Inode::new(S_IFREG, 0, 0);
+        inode.double_indirect = double_block;
+
+        let entries_per_block = (fs.block_size / 4) as u64;
+        let offset = (NUM_DIRECT as u64 + entries_per_block) * fs.block_size as u64;
+        assert_eq!(
+            inode.block_for_offset(&fs, offset).unwrap(),
+            Some(data_block)
+        );
+    }
+
+    #[test]
+    fn block_for_offset_resolves_triple_indirect() {
+        let mut fs = test_fs();
+        let triple_block = fs.alloc(BlockType::IndirectionTable).unwrap().unwrap();
+        let double_block = fs.alloc(BlockType::IndirectionTable).unwrap().unwrap();
+        let single_block = fs.alloc(BlockType::IndirectionTable).unwrap().unwrap();
+        let data_block = fs.alloc(BlockType::Data).unwrap().unwrap();
+        write_ptr(&mut fs, triple_block, 0, double_block);
+        write_ptr(&mut fs, double_block, 0, single_block);
+        write_ptr(&mut fs, single_block, 0, data_block);
+
+        let mut inode = Inode::new(S_IFREG, 0, 0);
+        inode.triple_indirect = triple_block;
+
+        let entries_per_block = (fs.block_size / 4) as u64;
+        let offset = (NUM_DIRECT as u64 + entries_per_block + entries_per_block * entries_per_block)
+            * fs.block_size as u64;
+        assert_eq!(
+            inode.block_for_offset(&fs, offset).unwrap(),
+            Some(data_block)
+        );
+    }
+
+    #[test]
+    fn block_for_offset_reports_a_hole() {
+        let fs = test_fs();
+        let inode = Inode::new(S_IFREG, 0, 0);
+
+        let offset = NUM_DIRECT as u64 * fs.block_size as u64;
+        assert_eq!(inode.block_for_offset(&fs, offset).unwrap(), None);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod block_table;
+pub mod dir_entry;
+pub mod inode;
+pub mod super_block;
@@ -1,4 +1,21 @@
-use std::{fs::File, os::unix::fs::FileExt};
+use thiserror::Error;
+
+use crate::volume::Volume;
+
+/// Arbitrary magic number identifying a dsfs image, checked on every mount so we don't
+/// happily interpret garbage as a filesystem.
+pub const MAGIC: u32 = 0xD5_F5_0001;
+pub const VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum SuperBlockError {
+    #[error("File IO Error")]
+    IoError(#[from] std::io::Error),
+    #[error("Bad magic number: expected {MAGIC:#010x}, found {0:#010x}")]
+    BadMagic(u32),
+    #[error("Unsupported version {0}, expected {VERSION}")]
+    UnsupportedVersion(u32),
+}
 
 pub struct SuperBlock {
     pub block_size: u32,
@@ -7,14 +24,27 @@ pub struct SuperBlock {
 
 impl SuperBlock {
     // Loads an existing filesystem from a block file
-    pub fn new(block_file: &File) -> std::io::Result<Self> {
+    pub fn new(volume: &impl Volume) -> Result<Self, SuperBlockError> {
+        let mut magic_buf = [0 as u8; 4];
+        volume.read_at(&mut magic_buf, 0)?;
+        let magic = u32::from_be_bytes(magic_buf);
+        if magic != MAGIC {
+            return Err(SuperBlockError::BadMagic(magic));
+        }
+
+        let mut version_buf = [0 as u8; 4];
+        volume.read_at(&mut version_buf, 4)?;
+        let version = u32::from_be_bytes(version_buf);
+        if version != VERSION {
+            return Err(SuperBlockError::UnsupportedVersion(version));
+        }
+
         let mut block_size_buf = [0 as u8; 4];
-        let _ = block_file.read_exact_at(&mut block_size_buf, 0)?;
-        // TODO: Check that this should not be u32::from_le_bytes() (im pretty sure this is right)
+        volume.read_at(&mut block_size_buf, 8)?;
         let block_size = u32::from_be_bytes(block_size_buf);
 
         let mut num_blocks_buf = [0 as u8; 4];
-        let _ = block_file.read_exact_at(&mut num_blocks_buf, 4)?;
+        volume.read_at(&mut num_blocks_buf, 12)?;
         let num_blocks = u32::from_be_bytes(num_blocks_buf);
 
         Ok(SuperBlock {
@@ -22,4 +52,15 @@ impl SuperBlock {
             num_blocks,
         })
     }
+
+    /// Writes the magic number, version, and this superblock's fields to block 0.
+    pub fn write(&self, volume: &mut impl Volume) -> Result<(), SuperBlockError> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&MAGIC.to_be_bytes());
+        bytes.extend_from_slice(&VERSION.to_be_bytes());
+        bytes.extend_from_slice(&self.block_size.to_be_bytes());
+        bytes.extend_from_slice(&self.num_blocks.to_be_bytes());
+        volume.write_at(&bytes, 0)?;
+        Ok(())
+    }
 }
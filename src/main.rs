@@ -1,249 +1,69 @@
 use clap::{crate_version, Arg, ArgAction, Command};
 use fuser::{
-    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
-    Request,
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, Request,
 };
-use libc::ENOENT;
+use libc::{EIO, ENOENT, ENOTDIR};
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{Seek, SeekFrom};
-use std::os::unix::fs::FileExt;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::time::{Duration, UNIX_EPOCH};
-use thiserror::Error;
+
+mod block_cache;
+mod dsfs;
+mod fs_structs;
+mod synced;
+mod volume;
+
+use block_cache::BlockCache;
+use dsfs::Dsfs;
+use fs_structs::dir_entry::{DirEntry, DirEntryIter, DirEntryType};
+use fs_structs::inode::{read_inode, Inode, InodeNumber};
+use synced::Synced;
+use volume::Volume;
 
 type BlockIndex = u32;
 type GroupIndex = u32;
 
 const BLOCK_SIZE: u32 = 4096; // 4KiB
 const NUM_BLOCKS: u32 = 1024; // 1024 Blocks = 4.0MiB ~= 4.2MB
-const BLOCKS_IN_GROUP: u32 = BLOCK_SIZE * 8; // Number of blocks in a group. This is limited by the
-                                             // number of bits in a free table, which is a single full block
 
 const TTL: Duration = Duration::from_secs(1); // 1 second
 
-const HELLO_DIR_ATTR: FileAttr = FileAttr {
-    ino: 1,
-    size: 0,
-    blocks: 0,
-    atime: UNIX_EPOCH, // 1970-01-01 00:00:00
-    mtime: UNIX_EPOCH,
-    ctime: UNIX_EPOCH,
-    crtime: UNIX_EPOCH,
-    kind: FileType::Directory,
-    perm: 0o755,
-    nlink: 2,
-    uid: 501,
-    gid: 20,
-    rdev: 0,
-    flags: 0,
-    blksize: 512,
-};
-
-const HELLO_TXT_CONTENT: &str = "Hello World!\n";
-
-const HELLO_TXT_ATTR: FileAttr = FileAttr {
-    ino: 2,
-    size: 13,
-    blocks: 1,
-    atime: UNIX_EPOCH, // 1970-01-01 00:00:00
-    mtime: UNIX_EPOCH,
-    ctime: UNIX_EPOCH,
-    crtime: UNIX_EPOCH,
-    kind: FileType::RegularFile,
-    perm: 0o644,
-    nlink: 1,
-    uid: 501,
-    gid: 20,
-    rdev: 0,
-    flags: 0,
-    blksize: 512,
-};
-
-struct Dsfs {
-    block_file: File,
-    mount_point: PathBuf,
-    block_size: u32,
-    num_blocks: u32,
-    blocks_in_group: u32,
-    free_tables: Vec<FreeTable>,
-}
-
-struct FreeTable {
-    table: [u8; BLOCKS_IN_GROUP as usize / 8],
-    group_index: GroupIndex,
-}
-
-#[derive(Error, Debug)]
-enum FreeTableError {
-    #[error("The bit index is out of bounds. Bit index provided: {0}, Max bit index: {1}")]
-    OutOfBounds(u32, u32),
-    #[error("File error")]
-    FileError,
-    #[error("Type cast error: From {0} to {1}")]
-    TypeCastError(&'static str, &'static str),
-}
-
-impl FreeTable {
-    // Creates a new free table, writes it to the disk, and returns it
-    fn create_and_init(
-        block_file: &mut File,
-        group_index: GroupIndex,
-    ) -> Result<Self, FreeTableError> {
-        let block_index = match group_index {
-            0 => 1,
-            _ => BLOCKS_IN_GROUP * group_index,
-        };
-        let mut table = [0 as u8; BLOCKS_IN_GROUP as usize / 8];
-        // TODO: Set initial bits
-        let free_table = FreeTable { table, group_index };
-        match free_table.update_file(block_file) {
-            Ok(_) => Ok(free_table),
-            Err(err) => Err(err),
-        }
-    }
-
-    // Creates a FileTable from an existing ft on the fs
-    fn from_fs(
-        block_file: &mut File,
-        group_index: GroupIndex,
-    ) -> Result<FreeTable, FreeTableError> {
-        let table = [0 as u8; BLOCKS_IN_GROUP as usize / 8];
-        let mut free_table = FreeTable { table, group_index };
-        match free_table.update_table(block_file) {
-            Ok(_) => Ok(free_table),
-            Err(err) => Err(err),
-        }
-    }
-
-    fn update_file(&self, block_file: &mut File) -> Result<(), FreeTableError> {
-        let block_index = match self.group_index {
-            0 => 1,
-            _ => BLOCKS_IN_GROUP * self.group_index,
-        };
-        match block_file.write_all_at(&self.table, (block_index * BLOCK_SIZE).into()) {
-            Ok(_) => Ok(()),
-            Err(_) => Err(FreeTableError::FileError),
-        }
-    }
-
-    fn update_table(&mut self, block_file: &File) -> Result<(), FreeTableError> {
-        let block_index = match self.group_index {
-            0 => 1,
-            _ => BLOCKS_IN_GROUP * self.group_index,
-        };
-        match block_file.read_exact_at(&mut self.table, (block_index * BLOCK_SIZE).into()) {
-            Ok(_) => Ok(()),
-            Err(_) => Err(FreeTableError::FileError),
-        }
-    }
-
-    fn set_bit(
-        &mut self,
-        block_file: &mut File,
-        bit_index: u32, // This is the index of the bit inside the current free table. This is NOT
-        // the same as the block index. It will be block_index % BLOCKS_IN_GROUP b/c it is the
-        // index of the block within a group
-        fs: &Dsfs,
-        value: bool,
-    ) -> Result<(), FreeTableError> {
-        // TODO: Check this condition (maybe off by 1)
-        if bit_index >= fs.blocks_in_group {
-            return Err(FreeTableError::OutOfBounds(bit_index, fs.blocks_in_group));
-        }
-        // Index of the [u8]
-        let arr_index: usize = match (bit_index / 8).try_into() {
-            Ok(ok) => ok,
-            Err(_) => {
-                return Err(FreeTableError::TypeCastError(
-                    std::any::type_name::<u32>(),
-                    std::any::type_name::<u32>(),
-                ))
-            }
-        };
-        let u8_index = 7 - (bit_index % 8); // Index of bit inside of the u8
-        match value {
-            true => self.table[arr_index] |= 0b1 << u8_index,
-            false => self.table[arr_index] &= 0b0 << u8_index,
-        };
-        Ok(())
-    }
-
-    fn get_bit(
-        &mut self,
-        block_file: &mut File,
-        bit_index: u32, // Ditto
-        fs: &Dsfs,
-    ) -> Result<bool, FreeTableError> {
-        // TODO: Check this condition (maybe off by 1)
-        if bit_index >= fs.blocks_in_group {
-            return Err(FreeTableError::OutOfBounds(bit_index, fs.blocks_in_group));
-        }
-        // Index of the [u8]
-        let arr_index: usize = match (bit_index / 8).try_into() {
-            Ok(ok) => ok,
-            Err(_) => {
-                return Err(FreeTableError::TypeCastError(
-                    std::any::type_name::<u32>(),
-                    std::any::type_name::<u32>(),
-                ))
-            }
-        };
-        let u8_index = 7 - (bit_index % 8); // Index of bit inside of the u8
-
-        // Theres gotta be a better way to do this
-        Ok(if self.table[arr_index] >> u8_index == 1 {
-            true
-        } else {
-            false
-        })
+fn dirent_file_type(file_type: DirEntryType) -> FileType {
+    match file_type {
+        DirEntryType::Directory => FileType::Directory,
+        DirEntryType::RegularFile | DirEntryType::Unknown => FileType::RegularFile,
     }
 }
 
-#[derive(Error, Debug)]
-enum DsfsError {}
-
-impl Dsfs {
-    // Loads an existing filesystem from a block file
-    fn new(file_name: PathBuf, mount_point: PathBuf) -> std::io::Result<Self> {
-        // Read superblock information
-        let block_file = File::open(file_name).unwrap();
-
-        let mut block_size_buf = [0 as u8; 4];
-        let _ = block_file.read_exact_at(&mut block_size_buf, 0)?;
-        // TODO: Check that this should not be u32::from_le_bytes() (im pretty sure this is right)
-        let block_size = u32::from_be_bytes(block_size_buf);
-
-        let mut num_blocks_buf = [0 as u8; 4];
-        let _ = block_file.read_exact_at(&mut num_blocks_buf, 4)?;
-        let num_blocks = u32::from_be_bytes(num_blocks_buf);
-
-        let mut blocks_in_group_buf = [0 as u8; 4];
-        let _ = block_file.read_exact_at(&mut blocks_in_group_buf, 8)?;
-        let blocks_in_group = u32::from_be_bytes(blocks_in_group_buf);
-
-        // Number of groups is ceil(num_blocks/blocks_in_group)
-        let num_groups = num_blocks.div_ceil(blocks_in_group);
-        let mut dsfs = Dsfs {
-            block_file,
-            mount_point,
-            block_size,
-            num_blocks,
-            blocks_in_group,
-            free_tables: vec![],
-        };
-        // For all groups, load a free table
-        for group_index in 0..num_groups {
-            dsfs.free_tables
-                .push(FreeTable::from_fs(&mut dsfs.block_file, group_index).unwrap())
-        }
-        Ok(dsfs)
+/// Builds the `FileAttr` fuser expects out of an on-disk `Inode`.
+fn inode_to_attr(ino: u64, inode: &Inode, block_size: u32) -> FileAttr {
+    FileAttr {
+        ino,
+        size: inode.size,
+        // FileAttr::blocks is always in 512-byte units regardless of the fs's own block_size.
+        blocks: inode.size.div_ceil(512),
+        atime: UNIX_EPOCH + Duration::from_secs(inode.atime),
+        mtime: UNIX_EPOCH + Duration::from_secs(inode.mtime),
+        ctime: UNIX_EPOCH + Duration::from_secs(inode.ctime),
+        crtime: UNIX_EPOCH,
+        kind: if inode.is_dir() {
+            FileType::Directory
+        } else {
+            FileType::RegularFile
+        },
+        perm: inode.mode & 0o7777,
+        nlink: inode.links_count as u32,
+        uid: inode.uid as u32,
+        gid: inode.gid as u32,
+        rdev: 0,
+        flags: 0,
+        blksize: block_size,
     }
-
-    // fn create(file_name: PathBuf, mount_point: PathBuf, block_size: u32, ) -> std::io::Result<Self> {
 }
 
-impl Filesystem for Dsfs {
+impl Filesystem for Synced<Dsfs<BlockCache<File>>> {
     fn init(
         &mut self,
         _req: &Request<'_>,
@@ -252,19 +72,54 @@ impl Filesystem for Dsfs {
         println!("Successfully Mounted");
         Ok(())
     }
+
+    fn destroy(&mut self) {
+        let _ = self.inner().block_file.flush();
+    }
+
+    fn fsync(&mut self, _req: &Request, _ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        match self.inner().block_file.flush() {
+            Ok(()) => reply.ok(),
+            Err(_) => reply.error(EIO),
+        }
+    }
+
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        if parent == 1 && name.to_str() == Some("hello.txt") {
-            reply.entry(&TTL, &HELLO_TXT_ATTR, 0);
-        } else {
+        let Some(name) = name.to_str() else {
+            reply.error(ENOENT);
+            return;
+        };
+        let fs = self.inner();
+        let parent_inode = match read_inode(&fs, parent as InodeNumber) {
+            Ok(inode) => inode,
+            Err(_) => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let found = DirEntryIter::new(&fs, &parent_inode).find_map(|entry| match entry {
+            Ok(entry) if entry.name == name => Some(entry.inode),
+            _ => None,
+        });
+        let Some(child_ino) = found else {
             reply.error(ENOENT);
+            return;
+        };
+        match read_inode(&fs, child_ino) {
+            Ok(child_inode) => reply.entry(
+                &TTL,
+                &inode_to_attr(child_ino as u64, &child_inode, fs.block_size),
+                0,
+            ),
+            Err(_) => reply.error(ENOENT),
         }
     }
 
     fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        match ino {
-            1 => reply.attr(&TTL, &HELLO_DIR_ATTR),
-            2 => reply.attr(&TTL, &HELLO_TXT_ATTR),
-            _ => reply.error(ENOENT),
+        let fs = self.inner();
+        match read_inode(&fs, ino as InodeNumber) {
+            Ok(inode) => reply.attr(&TTL, &inode_to_attr(ino, &inode, fs.block_size)),
+            Err(_) => reply.error(ENOENT),
         }
     }
 
@@ -274,16 +129,56 @@ impl Filesystem for Dsfs {
         ino: u64,
         _fh: u64,
         offset: i64,
-        _size: u32,
+        size: u32,
         _flags: i32,
         _lock: Option<u64>,
         reply: ReplyData,
     ) {
-        if ino == 2 {
-            reply.data(&HELLO_TXT_CONTENT.as_bytes()[offset as usize..]);
-        } else {
-            reply.error(ENOENT);
+        let fs = self.inner();
+        let inode = match read_inode(&fs, ino as InodeNumber) {
+            Ok(inode) => inode,
+            Err(_) => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let offset = offset as u64;
+        if offset >= inode.size {
+            reply.data(&[]);
+            return;
         }
+        let read_len = (inode.size - offset).min(size as u64);
+        // Zero-initialized so holes (blocks a sparse file never allocated) just stay zero.
+        let mut buf = vec![0u8; read_len as usize];
+        let mut buf_pos = 0usize;
+        let mut cur_offset = offset;
+        let mut remaining = read_len;
+        while remaining > 0 {
+            let offset_in_block = cur_offset % fs.block_size as u64;
+            let chunk_len = remaining.min(fs.block_size as u64 - offset_in_block);
+            match inode.block_for_offset(&fs, cur_offset) {
+                Ok(Some(block)) => {
+                    let block_offset = block as u64 * fs.block_size as u64 + offset_in_block;
+                    if fs
+                        .block_file
+                        .read_at(&mut buf[buf_pos..buf_pos + chunk_len as usize], block_offset)
+                        .is_err()
+                    {
+                        reply.error(EIO);
+                        return;
+                    }
+                }
+                Ok(None) => {} // hole; buf is already zeroed here
+                Err(_) => {
+                    reply.error(EIO);
+                    return;
+                }
+            }
+            buf_pos += chunk_len as usize;
+            cur_offset += chunk_len;
+            remaining -= chunk_len;
+        }
+        reply.data(&buf);
     }
 
     fn readdir(
@@ -294,20 +189,35 @@ impl Filesystem for Dsfs {
         offset: i64,
         mut reply: ReplyDirectory,
     ) {
-        if ino != 1 {
-            reply.error(ENOENT);
+        let fs = self.inner();
+        let inode = match read_inode(&fs, ino as InodeNumber) {
+            Ok(inode) => inode,
+            Err(_) => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        if !inode.is_dir() {
+            reply.error(ENOTDIR);
             return;
         }
-
-        let entries = vec![
-            (1, FileType::Directory, "."),
-            (1, FileType::Directory, ".."),
-            (2, FileType::RegularFile, "hello.txt"),
-        ];
+        let entries: Vec<DirEntry> =
+            match DirEntryIter::new(&fs, &inode).collect::<Result<_, _>>() {
+                Ok(entries) => entries,
+                Err(_) => {
+                    reply.error(EIO);
+                    return;
+                }
+            };
 
         for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
             // i + 1 means the index of the next entry
-            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+            if reply.add(
+                entry.inode as u64,
+                (i + 1) as i64,
+                dirent_file_type(entry.file_type),
+                entry.name,
+            ) {
                 break;
             }
         }
@@ -338,6 +248,7 @@ fn main() {
                 .required(false)
                 .short('c')
                 .long("create-fs")
+                .action(ArgAction::SetTrue)
                 .help("initializes a new filesystem at the given device file"),
         )
         .arg(
@@ -363,16 +274,18 @@ fn main() {
     if matches.get_flag("allow-root") {
         options.push(MountOption::AllowRoot);
     }
-    // println!("Mounting {} on {}", fs_filename.into(), mount_point.into());
-    let mut dsfs = Dsfs {
-        block_file: File::open(fs_filename).unwrap(),
-        mount_point: mount_point.to_path_buf(),
-        block_size: BLOCK_SIZE,
-        num_blocks: NUM_BLOCKS,
-        blocks_in_group: BLOCKS_IN_GROUP,
-        free_tables: vec![],
+
+    let dsfs = if matches.get_flag("create_fs") {
+        Dsfs::create_fs(
+            fs_filename.to_path_buf(),
+            mount_point.to_path_buf(),
+            NUM_BLOCKS,
+            BLOCK_SIZE,
+        )
+        .unwrap()
+    } else {
+        Dsfs::load(fs_filename.to_path_buf(), mount_point.to_path_buf()).unwrap()
     };
-    dsfs.free_tables
-        .push(FreeTable::create_and_init(&mut dsfs.block_file, 0).unwrap());
-    fuser::mount2(dsfs, mount_point, &options).unwrap();
+
+    fuser::mount2(Synced::new(dsfs), mount_point, &options).unwrap();
 }
@@ -0,0 +1,30 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Shares a `T` behind a mutex so it can be handed to callbacks that fire concurrently (like
+/// `fuser`'s `Filesystem` methods), the same "enforce thread-safety at the type level" approach
+/// ext2-rs uses for its own `Synced<T>`.
+pub struct Synced<T> {
+    inner: Arc<Mutex<T>>,
+}
+
+impl<T> Synced<T> {
+    pub fn new(value: T) -> Self {
+        Synced {
+            inner: Arc::new(Mutex::new(value)),
+        }
+    }
+
+    /// Locks the shared value. The returned guard holds the lock until it's dropped, so callers
+    /// should keep it scoped to a single callback's work.
+    pub fn inner(&self) -> MutexGuard<'_, T> {
+        self.inner.lock().unwrap()
+    }
+}
+
+impl<T> Clone for Synced<T> {
+    fn clone(&self) -> Self {
+        Synced {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
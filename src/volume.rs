@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+/// A random-access block device backing a `Dsfs`. Parameterizing the filesystem over this
+/// trait (rather than a concrete `std::fs::File`) mirrors ext2-rs's volume abstraction and
+/// lets tests build a filesystem image in RAM instead of needing a real file on disk.
+pub trait Volume {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()>;
+}
+
+impl Volume for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        FileExt::read_exact_at(self, buf, offset)
+    }
+
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        FileExt::write_all_at(self, buf, offset)
+    }
+}
+
+/// An in-memory volume backed by a fixed-size byte slice, for tests and other situations where
+/// there's no real block device to open.
+pub struct MemVolume {
+    data: Box<[u8]>,
+}
+
+impl MemVolume {
+    pub fn new(size: usize) -> Self {
+        MemVolume {
+            data: vec![0u8; size].into_boxed_slice(),
+        }
+    }
+}
+
+impl Volume for MemVolume {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let start = offset as usize;
+        let end = start + buf.len();
+        if end > self.data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "read past end of volume",
+            ));
+        }
+        buf.copy_from_slice(&self.data[start..end]);
+        Ok(())
+    }
+
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let start = offset as usize;
+        let end = start + buf.len();
+        if end > self.data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "write past end of volume",
+            ));
+        }
+        self.data[start..end].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mem_volume_round_trips_writes() {
+        let mut vol = MemVolume::new(16);
+        vol.write_at(&[1, 2, 3, 4], 4).unwrap();
+
+        let mut buf = [0u8; 4];
+        vol.read_at(&mut buf, 4).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn mem_volume_rejects_out_of_bounds_access() {
+        let mut vol = MemVolume::new(16);
+        assert!(vol.write_at(&[1, 2], 15).is_err());
+        assert!(vol.read_at(&mut [0u8; 2], 15).is_err());
+    }
+}